@@ -0,0 +1,216 @@
+use crate::{Command, DeviceAddress, GDeviceModel};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+const CONFIG_FILE_NAME: &str = "g213d.json";
+
+/// persisted daemon configuration
+///
+/// Keeps every command sent to each device model, in order, so they can be
+/// replayed after the daemon restarts, plus any commands saved against a specific
+/// device's `DeviceAddress`, which take priority so each physical unit
+/// restores its own state.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    commands: HashMap<String, Vec<Command>>,
+    #[serde(default)]
+    commands_by_address: HashMap<String, Vec<Command>>,
+    /// named, full lighting setups a user can switch between
+    #[serde(default)]
+    profiles: HashMap<String, Vec<Command>>,
+    #[serde(default)]
+    active_profile: Option<String>,
+}
+
+impl Config {
+    /// Load the config from the user's config directory, falling back to an
+    /// empty config if none exists yet or it cannot be parsed.
+    pub fn load() -> Self {
+        match Self::path().map(fs::read_to_string) {
+            Some(Ok(content)) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                error!("Failed to parse config, starting fresh: {}", err);
+                Self::default()
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    /// Persist the config to the user's config directory.
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+
+        if let Some(parent) = path.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                error!("Failed to create config directory {:?}: {}", parent, err);
+                return;
+            }
+        }
+
+        match serde_json::to_string_pretty(self) {
+            Ok(content) => {
+                if let Err(err) = fs::write(&path, content) {
+                    error!("Failed to write config to {:?}: {}", path, err);
+                }
+            }
+            Err(err) => error!("Failed to serialize config: {}", err),
+        }
+    }
+
+    /// Append `cmd` to the commands sent to `model` and persist it.
+    pub fn save_command(&mut self, model: &dyn GDeviceModel, cmd: Command) {
+        self.commands
+            .entry(model.get_name().to_string())
+            .or_default()
+            .push(cmd);
+        self.save();
+    }
+
+    /// The commands that should be replayed to devices of `model` on startup.
+    pub fn commands_for(&self, model: &dyn GDeviceModel) -> Vec<Command> {
+        self.commands
+            .get(model.get_name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Append `cmd` to the commands sent to the device at `address` and
+    /// persist it.
+    pub fn save_command_for(&mut self, address: &DeviceAddress, cmd: Command) {
+        self.commands_by_address
+            .entry(address.to_string())
+            .or_default()
+            .push(cmd);
+        self.save();
+    }
+
+    /// The commands that should be replayed to the device at `address` on
+    /// startup, if any were saved for it specifically.
+    pub fn commands_for_address(&self, address: &DeviceAddress) -> Vec<Command> {
+        self.commands_by_address
+            .get(&address.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Save `commands` as the named profile `name`, overwriting any
+    /// existing profile with that name.
+    pub fn save_profile(&mut self, name: &str, commands: Vec<Command>) {
+        self.profiles.insert(name.to_string(), commands);
+        self.save();
+    }
+
+    /// The commands making up the named profile, if it exists.
+    pub fn profile(&self, name: &str) -> Option<&[Command]> {
+        self.profiles.get(name).map(Vec::as_slice)
+    }
+
+    /// Names of all saved profiles.
+    pub fn list_profiles(&self) -> Vec<String> {
+        self.profiles.keys().cloned().collect()
+    }
+
+    /// Remember `name` as the active profile and persist it.
+    pub fn set_active_profile(&mut self, name: &str) {
+        self.active_profile = Some(name.to_string());
+        self.save();
+    }
+
+    /// The name of the profile that should be restored on startup, if any.
+    pub fn active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
+    /// The commands that should be replayed to a device of `model` at
+    /// `address`, preferring the active profile if one is set, then
+    /// commands saved for `address` specifically, then commands saved for
+    /// `model`.
+    pub fn resolve_commands(&self, model: &dyn GDeviceModel, address: &DeviceAddress) -> Vec<Command> {
+        if let Some(profile) = self.active_profile() {
+            if let Some(commands) = self.profile(profile) {
+                return commands.to_vec();
+            }
+        }
+
+        let commands = self.commands_for_address(address);
+        if commands.is_empty() {
+            self.commands_for(model)
+        } else {
+            commands
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::g213::G213Model;
+    use crate::Speed;
+
+    fn speed_command(value: u16) -> Command {
+        Command::Cycle(Speed::from(value))
+    }
+
+    fn speed_value(commands: &[Command]) -> u16 {
+        match commands {
+            [Command::Cycle(speed)] => speed.value(),
+            other => panic!("expected a single Cycle command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolve_commands_prefers_active_profile_over_everything() {
+        let model = G213Model::new();
+        let address = DeviceAddress::new("1-2:ABC123");
+        let mut config = Config::default();
+        config.profiles.insert("bright".to_string(), vec![speed_command(1)]);
+        config.active_profile = Some("bright".to_string());
+        config.commands_by_address.insert(address.to_string(), vec![speed_command(2)]);
+        config.commands.insert(model.get_name().to_string(), vec![speed_command(3)]);
+
+        assert_eq!(speed_value(&config.resolve_commands(&model, &address)), 1);
+    }
+
+    #[test]
+    fn resolve_commands_prefers_address_over_model() {
+        let model = G213Model::new();
+        let address = DeviceAddress::new("1-2:ABC123");
+        let mut config = Config::default();
+        config.commands_by_address.insert(address.to_string(), vec![speed_command(2)]);
+        config.commands.insert(model.get_name().to_string(), vec![speed_command(3)]);
+
+        assert_eq!(speed_value(&config.resolve_commands(&model, &address)), 2);
+    }
+
+    #[test]
+    fn resolve_commands_falls_back_to_model() {
+        let model = G213Model::new();
+        let address = DeviceAddress::new("unseen-address");
+        let mut config = Config::default();
+        config.commands.insert(model.get_name().to_string(), vec![speed_command(3)]);
+
+        assert_eq!(speed_value(&config.resolve_commands(&model, &address)), 3);
+    }
+
+    #[test]
+    fn serde_round_trip_preserves_profiles_and_active_profile() {
+        let mut config = Config::default();
+        config.profiles.insert("bright".to_string(), vec![speed_command(7)]);
+        config.active_profile = Some("bright".to_string());
+
+        let json = serde_json::to_string(&config).unwrap();
+        let restored: Config = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.active_profile(), Some("bright"));
+        assert_eq!(speed_value(restored.profile("bright").unwrap()), 7);
+    }
+}
@@ -0,0 +1,141 @@
+//! D-Bus front end for [`GDeviceManager`], so unprivileged clients (CLIs,
+//! desktop applets, ...) can drive the connected keyboards without opening
+//! the USB device themselves. The daemon stays the single owner of the USB
+//! handle; clients only ever talk to it over the bus.
+
+use crate::{Command, CommandError, GDeviceManager, RgbColor, Speed};
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::MatchRule;
+use dbus::{Error, Message, MethodErr};
+use dbus_crossroads::Crossroads;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+const SERVICE_NAME: &str = "de.r1tschy.g213d";
+const OBJECT_PATH: &str = "/de/r1tschy/g213d";
+
+/// How long to block waiting for an incoming method call before checking
+/// for a pending `DevicesChanged` notification.
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A device manager shared between the D-Bus dispatch thread and the rest
+/// of the daemon.
+pub type SharedDeviceManager = Arc<Mutex<GDeviceManager>>;
+
+/// Claim `de.r1tschy.g213d` on the session bus and serve `SetColorSector`,
+/// `Breathe`, `Cycle` and `Refresh` until the process exits. Blocks the
+/// calling thread, so run it on its own thread alongside `run_event_loop`.
+pub fn run(manager: SharedDeviceManager) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+
+    // The daemon is meant to be the single owner of the USB handle, so
+    // refuse to start (rather than silently stealing the name) if another
+    // instance already owns it, e.g. after a crash-restart race.
+    match connection.request_name(SERVICE_NAME, false, false, true)? {
+        RequestNameReply::PrimaryOwner => {}
+        other => {
+            return Err(Error::new_failed(&format!(
+                "{} is already owned by another process ({:?}); is g213d already running?",
+                SERVICE_NAME, other
+            )));
+        }
+    }
+
+    // `Connection` is `Send` but not `Sync` (it holds a `RefCell` of
+    // filters), so it can't be shared with `GDeviceManager`'s hotplug
+    // thread through the `devices_changed_callback`. Instead the callback
+    // only pushes a notification onto a channel; this thread, which owns
+    // the connection, drains it between polls and emits the signal itself.
+    let (devices_changed_tx, devices_changed_rx) = mpsc::channel();
+    manager
+        .lock()
+        .unwrap()
+        .set_devices_changed_callback(move || {
+            let _ = devices_changed_tx.send(());
+        });
+
+    let mut cr = Crossroads::new();
+    let iface = cr.register(SERVICE_NAME, |b| {
+        let m = manager.clone();
+        b.method(
+            "SetColorSector",
+            ("sector", "rgb"),
+            (),
+            move |_, _, (sector, rgb): (u8, String)| {
+                let color = parse_color("rgb", &rgb)?;
+                m.lock()
+                    .unwrap()
+                    .send_command(Command::ColorSector(color, Some(sector)));
+                Ok(())
+            },
+        );
+
+        let m = manager.clone();
+        b.method(
+            "Breathe",
+            ("rgb", "speed"),
+            (),
+            move |_, _, (rgb, speed): (String, u16)| {
+                let color = parse_color("rgb", &rgb)?;
+                m.lock()
+                    .unwrap()
+                    .send_command(Command::Breathe(color, Speed::from(speed)));
+                Ok(())
+            },
+        );
+
+        let m = manager.clone();
+        b.method("Cycle", ("speed",), (), move |_, _, (speed,): (u16,)| {
+            m.lock()
+                .unwrap()
+                .send_command(Command::Cycle(Speed::from(speed)));
+            Ok(())
+        });
+
+        let m = manager.clone();
+        b.method("Refresh", (), (), move |_, _, ()| {
+            m.lock().unwrap().refresh();
+            Ok(())
+        });
+
+        b.signal::<(), _>("DevicesChanged", ());
+    });
+    cr.insert(OBJECT_PATH, &[iface], ());
+
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            cr.handle_message(msg, conn).unwrap();
+            true
+        }),
+    );
+
+    loop {
+        connection.process(POLL_TIMEOUT)?;
+
+        while devices_changed_rx.try_recv().is_ok() {
+            let signal = Message::new_signal(OBJECT_PATH, SERVICE_NAME, "DevicesChanged")
+                .expect("DevicesChanged is a valid signal name");
+            if let Err(()) = connection.send(signal) {
+                error!("Failed to emit DevicesChanged signal");
+            }
+        }
+    }
+}
+
+fn parse_color(arg: &'static str, rgb: &str) -> Result<RgbColor, MethodErr> {
+    RgbColor::from_hex(rgb)
+        .map_err(|err| CommandError::InvalidArgument(arg, err.to_string()))
+        .map_err(to_method_err)
+}
+
+fn to_method_err(err: CommandError) -> MethodErr {
+    match err {
+        CommandError::InvalidArgument(arg, msg) => {
+            MethodErr::invalid_arg(&format!("{}: {}", arg, msg))
+        }
+        other => MethodErr::failed(&other.to_string()),
+    }
+}
@@ -0,0 +1,47 @@
+use crate::protocol::HidSectorDevice;
+use crate::{CommandResult, GDevice, GDeviceModel, RgbColor};
+use rusb::{Context, Device};
+
+pub const VENDOR_ID: u16 = 0x046d;
+pub const PRODUCT_ID: u16 = 0xc336;
+
+const INTERFACE: u16 = 1;
+const REPORT_ID: u8 = 0x11;
+const SECTORS: u8 = 5;
+
+/// Logitech G213 "Prodigy" keyboard
+pub struct G213Model;
+
+impl G213Model {
+    pub fn new() -> Self {
+        G213Model
+    }
+}
+
+impl Default for G213Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GDeviceModel for G213Model {
+    fn open_device(&self, device: Device<Context>) -> CommandResult<Box<dyn GDevice>> {
+        HidSectorDevice::open(device, self.get_name(), INTERFACE, REPORT_ID, SECTORS)
+    }
+
+    fn get_usb_ids(&self) -> Vec<(u16, u16)> {
+        vec![(VENDOR_ID, PRODUCT_ID)]
+    }
+
+    fn get_sectors(&self) -> u8 {
+        SECTORS
+    }
+
+    fn get_default_color(&self) -> RgbColor {
+        RgbColor(0xff, 0xff, 0xff)
+    }
+
+    fn get_name(&self) -> &'static str {
+        "G213"
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{CommandResult, GDeviceModelRef};
+use quick_error::ResultExt;
+use rusb::{Context, Device, Hotplug};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// A hotplug notification for a single [`GDeviceModel`](crate::GDeviceModel),
+/// identified by its `get_name()`.
+pub enum HotplugEvent {
+    Arrived(&'static str, Device<Context>),
+    Left(&'static str, Device<Context>),
+}
+
+struct HotplugCallback {
+    model_name: &'static str,
+    tx: Sender<HotplugEvent>,
+}
+
+impl Hotplug<Context> for HotplugCallback {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        let _ = self.tx.send(HotplugEvent::Arrived(self.model_name, device));
+    }
+
+    fn device_left(&mut self, device: Device<Context>) {
+        let _ = self.tx.send(HotplugEvent::Left(self.model_name, device));
+    }
+}
+
+/// Register one hotplug callback per (model, USB ID) pair, and return the
+/// receiving end of the channel the callbacks report arrivals/removals on.
+///
+/// The returned registrations must be kept alive for as long as hotplug
+/// notifications are wanted; dropping a `Registration` deregisters its
+/// callback.
+pub(crate) fn register(
+    context: &Context,
+    models: &[GDeviceModelRef],
+) -> CommandResult<(Receiver<HotplugEvent>, Vec<rusb::Registration<Context>>)> {
+    let (tx, rx) = channel();
+    let mut registrations = Vec::new();
+
+    for model in models {
+        for (vendor_id, product_id) in model.get_usb_ids() {
+            let callback = HotplugCallback {
+                model_name: model.get_name(),
+                tx: tx.clone(),
+            };
+            let registration = rusb::HotplugBuilder::new()
+                .vendor_id(vendor_id)
+                .product_id(product_id)
+                .enumerate(false)
+                .register(context, Box::new(callback))
+                .context("registering USB hotplug callback")?;
+            registrations.push(registration);
+        }
+    }
+
+    Ok((rx, registrations))
+}
@@ -6,19 +6,26 @@ extern crate log;
 extern crate quick_error;
 
 use crate::config::Config;
-use crate::g213::G213Model;
+use crate::registry::ModelRegistry;
 use hex::FromHexError;
 use quick_error::ResultExt;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::sync::mpsc;
+use std::time::Duration;
 
 pub mod config;
+pub mod dbus_service;
 pub mod g213;
+pub mod hotplug;
+pub mod protocol;
+pub mod registry;
 pub mod usb_ext;
 
 /// RGB color
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RgbColor(pub u8, pub u8, pub u8);
 
 impl RgbColor {
@@ -50,7 +57,7 @@ impl RgbColor {
 }
 
 /// speed of effect
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub struct Speed(u16);
 
 impl From<u16> for Speed {
@@ -59,8 +66,14 @@ impl From<u16> for Speed {
     }
 }
 
+impl Speed {
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
 /// command to send to device to change color
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Command {
     ColorSector(RgbColor, Option<u8>),
     Breathe(RgbColor, Speed),
@@ -68,8 +81,40 @@ pub enum Command {
 }
 
 /// model series
-pub trait GDeviceModel {
-    fn find(&self, ctx: &DeviceList<Context>) -> Vec<Box<dyn GDevice>>;
+pub trait GDeviceModel: Send {
+    /// Find every connected device matching this model's USB IDs. The
+    /// default implementation routes by `get_usb_ids` instead of each model
+    /// having to scan and filter the whole `DeviceList` itself.
+    fn find(&self, usb_devices: &DeviceList<Context>) -> Vec<Box<dyn GDevice>> {
+        usb_devices
+            .iter()
+            .filter(|device| self.matches(device))
+            .filter_map(|device| match self.open_device(device) {
+                Ok(device) => Some(device),
+                Err(err) => {
+                    error!("Failed to open {} device: {:?}", self.get_name(), err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Whether `device`'s vendor/product ID is one this model matches.
+    fn matches(&self, device: &rusb::Device<Context>) -> bool {
+        match device.device_descriptor() {
+            Ok(desc) => self
+                .get_usb_ids()
+                .contains(&(desc.vendor_id(), desc.product_id())),
+            Err(_) => false,
+        }
+    }
+
+    /// Open a single device already known to match this model, e.g. one
+    /// reported by a USB hotplug `Arrived` event.
+    fn open_device(&self, device: rusb::Device<Context>) -> CommandResult<Box<dyn GDevice>>;
+
+    /// The USB vendor/product ID pairs devices of this model report.
+    fn get_usb_ids(&self) -> Vec<(u16, u16)>;
 
     fn get_sectors(&self) -> u8;
 
@@ -80,10 +125,54 @@ pub trait GDeviceModel {
 
 pub type GDeviceModelRef = Box<dyn GDeviceModel>;
 
+/// identity and capabilities of a connected device; `serial` and `firmware`
+/// are read back from it, `sectors` is the count its model reports
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub serial: String,
+    pub firmware: String,
+    pub sectors: u8,
+}
+
+/// Stable identifier for a physical device, derived from its USB bus/port
+/// path and serial number. Unlike the OS-assigned bus/address pair, it
+/// survives replugging the device into the same port, so it can be used to key saved commands
+/// and to target one of several identical keyboards.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeviceAddress(String);
+
+impl DeviceAddress {
+    pub fn new(address: impl Into<String>) -> Self {
+        DeviceAddress(address.into())
+    }
+}
+
+impl fmt::Display for DeviceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// a device
-pub trait GDevice {
-    fn get_debug_info(&self) -> String;
+pub trait GDevice: Send {
+    fn get_debug_info(&mut self) -> String;
     fn send_command(&mut self, cmd: Command) -> CommandResult<()>;
+
+    /// Read the device's serial number and firmware/protocol version, plus
+    /// the number of addressable sectors its model reports, caching the
+    /// result for subsequent calls.
+    fn query_info(&mut self) -> CommandResult<DeviceInfo>;
+
+    /// The last result of `query_info`, if it has been called yet.
+    fn cached_info(&self) -> Option<DeviceInfo>;
+
+    /// Stable address identifying this physical device, so commands can be
+    /// targeted at it specifically and its state restored across replugs.
+    fn address(&self) -> DeviceAddress;
+
+    /// USB bus number and device address, used to recognise this device
+    /// again when it is unplugged.
+    fn location(&self) -> (u8, u8);
 }
 
 pub type GDeviceRef = Box<dyn GDevice>;
@@ -119,40 +208,168 @@ impl Hash for Box<dyn GDeviceModel> {
     }
 }
 
+/// How long `run_event_loop` may block waiting for a libusb event before
+/// returning control to its caller, so a lock held on a shared
+/// `GDeviceManager` is only ever held briefly rather than indefinitely.
+const HOTPLUG_POLL_TIMEOUT: Duration = Duration::from_millis(200);
+
 pub struct GDeviceManager {
     _context: Context,
     config: Config,
     devices: HashMap<GDeviceModelRef, Vec<GDeviceRef>>,
+    hotplug_events: Option<mpsc::Receiver<hotplug::HotplugEvent>>,
+    _hotplug_registrations: Vec<rusb::Registration<Context>>,
+    devices_changed_callback: Option<Box<dyn Fn() + Send>>,
 }
 
 impl GDeviceManager {
     fn get_models() -> Vec<Box<dyn GDeviceModel>> {
-        vec![Box::new(G213Model::new())]
+        ModelRegistry::with_defaults().into_models()
     }
 
     /// Try to create device manager with USB connection
     pub fn try_new() -> CommandResult<Self> {
         let context = Context::new().context("creating USB context")?;
         let usb_devices = context.devices().context("listing USB devices")?;
-        let devices = Self::find_devices(&usb_devices);
+        let models = Self::get_models();
+        let (hotplug_events, hotplug_registrations) = Self::register_hotplug(&context, &models);
+        let devices = Self::find_devices(models, &usb_devices);
         let config = Config::load();
 
         let mut self_ = Self {
             _context: context,
             devices,
             config,
+            hotplug_events,
+            _hotplug_registrations: hotplug_registrations,
+            devices_changed_callback: None,
         };
         self_.send();
         Ok(self_)
     }
 
+    /// Register a callback invoked whenever the set of connected devices
+    /// changes, e.g. so a D-Bus front end can emit a `DevicesChanged` signal.
+    pub fn set_devices_changed_callback(&mut self, callback: impl Fn() + Send + 'static) {
+        self.devices_changed_callback = Some(Box::new(callback));
+    }
+
+    fn notify_devices_changed(&self) {
+        if let Some(callback) = &self.devices_changed_callback {
+            callback();
+        }
+    }
+
+    /// Register USB hotplug callbacks for every model, falling back to the
+    /// one-shot enumeration done by `try_new` if the local libusb build
+    /// lacks hotplug capability or registration otherwise fails.
+    fn register_hotplug(
+        context: &Context,
+        models: &[GDeviceModelRef],
+    ) -> (
+        Option<mpsc::Receiver<hotplug::HotplugEvent>>,
+        Vec<rusb::Registration<Context>>,
+    ) {
+        if !rusb::has_hotplug() {
+            warn!("libusb was built without hotplug support, devices will not be reapplied automatically");
+            return (None, Vec::new());
+        }
+
+        match hotplug::register(context, models) {
+            Ok((rx, registrations)) => (Some(rx), registrations),
+            Err(err) => {
+                error!("Failed to register USB hotplug callbacks: {:?}", err);
+                (None, Vec::new())
+            }
+        }
+    }
+
+    /// Pump the libusb event loop so hotplug callbacks fire, blocking for up
+    /// to [`HOTPLUG_POLL_TIMEOUT`] waiting for libusb to have an event to
+    /// dispatch. Newly arrived devices are opened and immediately replayed
+    /// the current config for their model; devices that are removed are
+    /// dropped from the device map.
+    ///
+    /// `self` is typically shared behind a mutex with a D-Bus front end
+    /// (see [`dbus_service::run`](crate::dbus_service::run)), so this must
+    /// not block indefinitely: an unbounded wait here would hold the lock
+    /// until the next USB arrival/removal, starving every D-Bus method call.
+    pub fn run_event_loop(&mut self) {
+        if self.hotplug_events.is_none() {
+            return;
+        }
+
+        if let Err(err) = self._context.handle_events(Some(HOTPLUG_POLL_TIMEOUT)) {
+            error!("Failed to handle USB events: {}", err);
+            return;
+        }
+
+        while let Ok(event) = self.hotplug_events.as_ref().unwrap().try_recv() {
+            self.handle_hotplug_event(event);
+        }
+    }
+
+    fn handle_hotplug_event(&mut self, event: hotplug::HotplugEvent) {
+        let changed = match event {
+            hotplug::HotplugEvent::Arrived(model_name, device) => {
+                let entry = self
+                    .devices
+                    .iter_mut()
+                    .find(|(model, _)| model.get_name() == model_name);
+                let (model, devices) = match entry {
+                    Some(entry) => entry,
+                    None => return,
+                };
+
+                match model.open_device(device) {
+                    Ok(mut new_device) => {
+                        let commands =
+                            Self::effective_commands(&self.config, model.deref(), &new_device.address());
+                        for command in commands {
+                            if let Err(err) = new_device.send_command(command) {
+                                error!("Sending command to newly connected device failed: {:?}", err);
+                            }
+                        }
+                        info!("{} (re)connected", model_name);
+                        devices.push(new_device);
+                        true
+                    }
+                    Err(err) => {
+                        error!("Failed to open newly connected {} device: {:?}", model_name, err);
+                        false
+                    }
+                }
+            }
+            hotplug::HotplugEvent::Left(model_name, device) => {
+                let mut removed = false;
+                if let Some((_, devices)) = self
+                    .devices
+                    .iter_mut()
+                    .find(|(model, _)| model.get_name() == model_name)
+                {
+                    let location = (device.bus_number(), device.address());
+                    let before = devices.len();
+                    devices.retain(|d| d.location() != location);
+                    removed = devices.len() != before;
+                }
+                info!("{} disconnected", model_name);
+                removed
+            }
+        };
+
+        if changed {
+            self.notify_devices_changed();
+        }
+    }
+
     fn find_devices(
+        models: Vec<GDeviceModelRef>,
         usb_devices: &DeviceList<Context>,
     ) -> HashMap<GDeviceModelRef, Vec<GDeviceRef>> {
-        Self::get_models()
+        models
             .into_iter()
             .map(|model| {
-                let devices = model.find(&usb_devices);
+                let devices = model.find(usb_devices);
                 (model, devices)
             })
             .collect()
@@ -171,11 +388,84 @@ impl GDeviceManager {
         }
     }
 
-    /// Send current config to device
+    /// Send a command to exactly one device, identified by its stable
+    /// `DeviceAddress`, instead of broadcasting it to every connected
+    /// keyboard.
+    pub fn send_command_to(&mut self, target: &DeviceAddress, cmd: Command) {
+        for devices in self.devices.values_mut() {
+            if let Some(device) = devices.iter_mut().find(|device| device.address() == *target) {
+                if let Err(err) = device.send_command(cmd.clone()) {
+                    error!("Sending command failed for device: {:?}", err);
+                }
+                self.config.save_command_for(target, cmd);
+                return;
+            }
+        }
+        warn!("No connected device with address {}", target);
+    }
+
+    /// List every connected device's address and identity info.
+    pub fn list_devices(&mut self) -> Vec<(DeviceAddress, DeviceInfo)> {
+        self.devices
+            .values_mut()
+            .flatten()
+            .filter_map(|device| {
+                let address = device.address();
+                match device.query_info() {
+                    Ok(info) => Some((address, info)),
+                    Err(err) => {
+                        error!("Failed to query device info for {}: {:?}", address, err);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Send current config to device, preferring the active profile if one
+    /// is set, then a device's own saved commands, then the model-wide ones,
+    /// then a sensible built-in default if none of those have ever been set.
     pub fn send(&mut self) {
         for (model, devices) in &mut self.devices {
-            for command in self.config.commands_for(model.deref()) {
-                for device in devices.iter_mut() {
+            for device in devices.iter_mut() {
+                let commands = Self::effective_commands(&self.config, model.deref(), &device.address());
+                for command in commands {
+                    if let Err(err) = device.send_command(command) {
+                        error!("Sending command failed for device: {:?}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The commands that should be replayed to a device of `model` at
+    /// `address`, falling back to lighting every sector in the model's
+    /// default color if nothing has ever been saved for it.
+    fn effective_commands(
+        config: &Config,
+        model: &dyn GDeviceModel,
+        address: &DeviceAddress,
+    ) -> Vec<Command> {
+        let commands = config.resolve_commands(model, address);
+        if commands.is_empty() {
+            Self::default_commands(model)
+        } else {
+            commands
+        }
+    }
+
+    /// Light every sector of `model` in its default color, used the first
+    /// time a device is seen and nothing has been saved for it yet.
+    fn default_commands(model: &dyn GDeviceModel) -> Vec<Command> {
+        (0..model.get_sectors())
+            .map(|sector| Command::ColorSector(model.get_default_color(), Some(sector)))
+            .collect()
+    }
+
+    fn broadcast(&mut self, commands: &[Command]) {
+        for devices in self.devices.values_mut() {
+            for device in devices.iter_mut() {
+                for command in commands {
                     if let Err(err) = device.send_command(command.clone()) {
                         error!("Sending command failed for device: {:?}", err);
                     }
@@ -184,6 +474,33 @@ impl GDeviceManager {
         }
     }
 
+    /// Replay a named profile to every connected device and remember it as
+    /// the active profile, so it is restored again on the next startup.
+    pub fn apply_profile(&mut self, name: &str) -> CommandResult<()> {
+        let commands = self
+            .config
+            .profile(name)
+            .ok_or_else(|| {
+                CommandError::InvalidArgument("name", format!("no such profile: {}", name))
+            })?
+            .to_vec();
+
+        self.broadcast(&commands);
+        self.config.set_active_profile(name);
+        Ok(())
+    }
+
+    /// Save `commands` as a named profile, e.g. a full sector/breathe/cycle
+    /// setup, so it can be replayed later via `apply_profile`.
+    pub fn save_profile(&mut self, name: &str, commands: Vec<Command>) {
+        self.config.save_profile(name, commands);
+    }
+
+    /// Names of all saved profiles.
+    pub fn list_profiles(&self) -> Vec<String> {
+        self.config.list_profiles()
+    }
+
     /// Refresh config from filesystem and send config
     pub fn refresh(&mut self) {
         self.config = Config::load();
@@ -193,8 +510,28 @@ impl GDeviceManager {
 
 impl fmt::Debug for GDeviceManager {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("GDeviceManager")
-            .field(&self.devices.len())
-            .finish()
+        let mut debug = f.debug_struct("GDeviceManager");
+        for (model, devices) in &self.devices {
+            let infos: Vec<Option<DeviceInfo>> = devices.iter().map(|d| d.cached_info()).collect();
+            debug.field(model.get_name(), &infos);
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_address_displays_as_the_wrapped_string() {
+        let address = DeviceAddress::new("1-2:ABC123");
+        assert_eq!(address.to_string(), "1-2:ABC123");
+    }
+
+    #[test]
+    fn device_addresses_with_the_same_value_are_equal() {
+        assert_eq!(DeviceAddress::new("1-2:ABC123"), DeviceAddress::new("1-2:ABC123"));
+        assert_ne!(DeviceAddress::new("1-2:ABC123"), DeviceAddress::new("1-3:XYZ789"));
     }
 }
@@ -0,0 +1,221 @@
+//! The "5-sector hex" HID lighting protocol shared by the G213 and its
+//! siblings (G413, G512, ...): a single feature report carrying a sector
+//! index, an RGB color and an optional breathe/cycle speed. Models built on
+//! this protocol only differ in their USB IDs, sector count and default
+//! color, so they can all share [`HidSectorDevice`] instead of reimplementing
+//! [`GDevice`] from scratch.
+
+use crate::usb_ext::UsbDeviceHandleExt;
+use crate::{Command, CommandResult, DeviceAddress, DeviceInfo, GDevice};
+use quick_error::ResultExt;
+use rusb::{Context, Device, DeviceHandle};
+
+const INFO_REPORT_ID: u8 = 0x01;
+
+/// Encode `cmd` as a feature report for a device using the shared protocol.
+pub fn encode_command(report_id: u8, cmd: &Command) -> Vec<u8> {
+    match cmd {
+        Command::ColorSector(color, sector) => vec![
+            report_id,
+            0xff,
+            0x0c,
+            0x3a,
+            sector.unwrap_or(0),
+            0x01,
+            color.red(),
+            color.green(),
+            color.blue(),
+            0x02,
+        ],
+        Command::Breathe(color, speed) => vec![
+            report_id,
+            0xff,
+            0x0c,
+            0x3a,
+            0x00,
+            0x02,
+            color.red(),
+            color.green(),
+            color.blue(),
+            (speed.value() >> 8) as u8,
+            speed.value() as u8,
+        ],
+        Command::Cycle(speed) => vec![
+            report_id,
+            0xff,
+            0x0c,
+            0x3a,
+            0x00,
+            0x03,
+            0x00,
+            0x00,
+            0x00,
+            (speed.value() >> 8) as u8,
+            speed.value() as u8,
+        ],
+    }
+}
+
+/// Derive a stable address from the device's USB bus/port path plus its
+/// serial number, so the same physical keyboard keeps its address across
+/// replugs into the same port even though the OS-assigned bus/device
+/// numbers don't. Plugging it into a different port changes the address.
+fn compute_address(device: &Device<Context>, handle: &DeviceHandle<Context>) -> DeviceAddress {
+    let ports = device
+        .port_numbers()
+        .unwrap_or_default()
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(".");
+    let path = format!("{}-{}", device.bus_number(), ports);
+
+    let serial = device
+        .device_descriptor()
+        .ok()
+        .and_then(|descriptor| handle.read_serial_number_string_ascii(&descriptor).ok());
+
+    match serial {
+        Some(serial) => DeviceAddress::new(format!("{}:{}", path, serial)),
+        None => DeviceAddress::new(path),
+    }
+}
+
+/// A device speaking the shared "5-sector hex" protocol.
+pub struct HidSectorDevice {
+    handle: DeviceHandle<Context>,
+    address: DeviceAddress,
+    info: Option<DeviceInfo>,
+    name: &'static str,
+    interface: u16,
+    report_id: u8,
+    sectors: u8,
+}
+
+impl HidSectorDevice {
+    pub fn open(
+        device: Device<Context>,
+        name: &'static str,
+        interface: u16,
+        report_id: u8,
+        sectors: u8,
+    ) -> CommandResult<Box<dyn GDevice>> {
+        let handle = device.open().context("opening device")?;
+        let address = compute_address(&device, &handle);
+        Ok(Box::new(HidSectorDevice {
+            handle,
+            address,
+            info: None,
+            name,
+            interface,
+            report_id,
+            sectors,
+        }))
+    }
+}
+
+impl GDevice for HidSectorDevice {
+    fn get_debug_info(&mut self) -> String {
+        let (bus, address) = self.location();
+        match self.query_info() {
+            Ok(info) => format!(
+                "{} (bus {}, address {}, serial {}, firmware {}, {} sectors)",
+                self.name, bus, address, info.serial, info.firmware, info.sectors
+            ),
+            Err(err) => format!(
+                "{} (bus {}, address {}, info unavailable: {:?})",
+                self.name, bus, address, err
+            ),
+        }
+    }
+
+    fn send_command(&mut self, cmd: Command) -> CommandResult<()> {
+        let packet = encode_command(self.report_id, &cmd);
+        self.handle
+            .write_feature_report(self.interface, self.report_id, &packet)
+            .context("sending command to device")?;
+        Ok(())
+    }
+
+    fn query_info(&mut self) -> CommandResult<DeviceInfo> {
+        if let Some(info) = &self.info {
+            return Ok(info.clone());
+        }
+
+        let descriptor = self
+            .handle
+            .device()
+            .device_descriptor()
+            .context("reading device descriptor")?;
+        let serial = self
+            .handle
+            .read_serial_number_string_ascii(&descriptor)
+            .unwrap_or_else(|_| "unknown".to_string());
+
+        let mut report = [0u8; 8];
+        self.handle
+            .read_feature_report(self.interface, INFO_REPORT_ID, &mut report)
+            .context("reading device firmware info")?;
+
+        let info = DeviceInfo {
+            serial,
+            firmware: format!("{}.{}", report[1], report[2]),
+            // not read from the device: the count given to `open`
+            sectors: self.sectors,
+        };
+        self.info = Some(info.clone());
+        Ok(info)
+    }
+
+    fn cached_info(&self) -> Option<DeviceInfo> {
+        self.info.clone()
+    }
+
+    fn address(&self) -> DeviceAddress {
+        self.address.clone()
+    }
+
+    fn location(&self) -> (u8, u8) {
+        let device = self.handle.device();
+        (device.bus_number(), device.address())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RgbColor, Speed};
+
+    #[test]
+    fn encode_color_sector_matches_the_5_sector_hex_layout() {
+        let packet = encode_command(0x11, &Command::ColorSector(RgbColor(0x11, 0x22, 0x33), Some(2)));
+        assert_eq!(packet, vec![0x11, 0xff, 0x0c, 0x3a, 2, 0x01, 0x11, 0x22, 0x33, 0x02]);
+    }
+
+    #[test]
+    fn encode_color_sector_defaults_missing_sector_to_zero() {
+        let packet = encode_command(0x11, &Command::ColorSector(RgbColor(0, 0, 0), None));
+        assert_eq!(packet[4], 0);
+    }
+
+    #[test]
+    fn encode_breathe_matches_the_5_sector_hex_layout() {
+        let packet = encode_command(
+            0x11,
+            &Command::Breathe(RgbColor(0x11, 0x22, 0x33), Speed::from(0x0102)),
+        );
+        assert_eq!(
+            packet,
+            vec![0x11, 0xff, 0x0c, 0x3a, 0x00, 0x02, 0x11, 0x22, 0x33, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn encode_cycle_matches_the_5_sector_hex_layout() {
+        let packet = encode_command(0x11, &Command::Cycle(Speed::from(0x0304)));
+        assert_eq!(
+            packet,
+            vec![0x11, 0xff, 0x0c, 0x3a, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x04]
+        );
+    }
+}
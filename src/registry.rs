@@ -0,0 +1,59 @@
+use crate::g213::G213Model;
+use crate::GDeviceModelRef;
+
+/// Runtime registry of known device models. Adding support for a sibling
+/// Logitech device (G413, G512, ...) only means calling `register` with a
+/// new `GDeviceModel`, not editing `GDeviceManager`.
+pub struct ModelRegistry {
+    models: Vec<GDeviceModelRef>,
+}
+
+impl ModelRegistry {
+    pub fn new() -> Self {
+        ModelRegistry { models: Vec::new() }
+    }
+
+    /// The registry pre-populated with every model this crate ships.
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(G213Model::new()));
+        registry
+    }
+
+    pub fn register(&mut self, model: GDeviceModelRef) {
+        self.models.push(model);
+    }
+
+    pub fn into_models(self) -> Vec<GDeviceModelRef> {
+        self.models
+    }
+}
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::with_defaults()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_registry_has_no_models() {
+        assert!(ModelRegistry::new().into_models().is_empty());
+    }
+
+    #[test]
+    fn with_defaults_includes_g213() {
+        let models = ModelRegistry::with_defaults().into_models();
+        assert!(models.iter().any(|model| model.get_name() == "G213"));
+    }
+
+    #[test]
+    fn register_adds_a_model() {
+        let mut registry = ModelRegistry::new();
+        registry.register(Box::new(G213Model::new()));
+        assert_eq!(registry.into_models().len(), 1);
+    }
+}
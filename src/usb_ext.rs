@@ -0,0 +1,64 @@
+use rusb::{Context, DeviceHandle, Direction, Recipient, RequestType};
+use std::time::Duration;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_millis(500);
+const HID_SET_REPORT: u8 = 0x09;
+const HID_GET_REPORT: u8 = 0x01;
+const HID_REPORT_TYPE_FEATURE: u16 = 0x03;
+
+/// Convenience helpers for talking to HID keyboards that only expose a
+/// feature report via control transfers, rather than a plain interrupt OUT
+/// endpoint.
+pub trait UsbDeviceHandleExt {
+    /// Send a HID feature report to `interface` with the given `report_id`.
+    fn write_feature_report(
+        &self,
+        interface: u16,
+        report_id: u8,
+        data: &[u8],
+    ) -> rusb::Result<usize>;
+
+    /// Read a HID feature report from `interface` with the given `report_id`.
+    fn read_feature_report(
+        &self,
+        interface: u16,
+        report_id: u8,
+        buf: &mut [u8],
+    ) -> rusb::Result<usize>;
+}
+
+impl UsbDeviceHandleExt for DeviceHandle<Context> {
+    fn write_feature_report(
+        &self,
+        interface: u16,
+        report_id: u8,
+        data: &[u8],
+    ) -> rusb::Result<usize> {
+        let request_type = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.write_control(
+            request_type,
+            HID_SET_REPORT,
+            (HID_REPORT_TYPE_FEATURE << 8) | report_id as u16,
+            interface,
+            data,
+            CONTROL_TIMEOUT,
+        )
+    }
+
+    fn read_feature_report(
+        &self,
+        interface: u16,
+        report_id: u8,
+        buf: &mut [u8],
+    ) -> rusb::Result<usize> {
+        let request_type = rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        self.read_control(
+            request_type,
+            HID_GET_REPORT,
+            (HID_REPORT_TYPE_FEATURE << 8) | report_id as u16,
+            interface,
+            buf,
+            CONTROL_TIMEOUT,
+        )
+    }
+}